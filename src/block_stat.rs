@@ -1,6 +1,6 @@
 use std::{
     time::Duration,
-    collections::BTreeMap,
+    collections::{ BTreeMap, VecDeque },
 };
 
 pub struct BlockStatReport {
@@ -8,6 +8,15 @@ pub struct BlockStatReport {
     avg_time: Duration,
     global_percents: f32,
     relative_parent_percents: f32,
+    last_frame_time: Duration,
+    windowed_avg_time: Duration,
+    windowed_max_time: Duration,
+    #[cfg(feature = "track_allocations")]
+    total_bytes_allocated: u64,
+    #[cfg(feature = "track_allocations")]
+    bytes_per_call: u64,
+    #[cfg(feature = "track_allocations")]
+    alloc_count: u64,
     children: Vec<BlockStatReport>,
 }
 
@@ -26,11 +35,28 @@ impl BlockStatReport {
             "<td>{:6.2} %</td>",
             "<td>{:6.2} %</td>",
             "<td>{:9.4} ms</td>",
-            "</tr>\n"
+            "<td>{:9.4} ms</td>",
+            "<td>{:9.4} ms</td>",
+            "<td>{:9.4} ms</td>",
             ),
-            depth*25, name, self.global_percents, self.relative_parent_percents, self.avg_time.as_secs_f32()*1000.0
+            depth*25, name, self.global_percents, self.relative_parent_percents, self.avg_time.as_secs_f32()*1000.0,
+            self.last_frame_time.as_secs_f32()*1000.0, self.windowed_avg_time.as_secs_f32()*1000.0, self.windowed_max_time.as_secs_f32()*1000.0
         );
 
+        #[cfg(feature = "track_allocations")]
+        {
+            *report += &format!(
+                concat!(
+                "<td>{} B</td>",
+                "<td>{} B</td>",
+                "<td>{}</td>",
+                ),
+                self.total_bytes_allocated, self.bytes_per_call, self.alloc_count
+            );
+        }
+
+        *report += "</tr>\n";
+
         self.children.sort_by(|a, b| b.relative_parent_percents.partial_cmp(&a.relative_parent_percents).unwrap());
 
         for child in self.children.iter_mut() {
@@ -45,15 +71,73 @@ pub struct BlockStat {
     pub(crate) total_time: Duration,
     pub(crate) measure_count: u32,
     pub(crate) children: BTreeMap<usize, BlockStat>,
+    pub(crate) frame_window_size: usize,
+    pub(crate) prev_total_time: Duration,
+    pub(crate) prev_measure_count: u32,
+    pub(crate) frame_window: VecDeque<(Duration, u32)>,
+    #[cfg(feature = "track_allocations")]
+    pub(crate) total_bytes_allocated: u64,
+    #[cfg(feature = "track_allocations")]
+    pub(crate) alloc_count: u64,
 }
 
 impl BlockStat {
-    pub fn new(name: &'static str) -> BlockStat {
+    pub fn new(name: &'static str, frame_window_size: usize) -> BlockStat {
         BlockStat {
             name,
             total_time: Duration::from_millis(0),
             measure_count: 0,
             children: BTreeMap::new(),
+            frame_window_size,
+            prev_total_time: Duration::from_millis(0),
+            prev_measure_count: 0,
+            frame_window: VecDeque::with_capacity(frame_window_size),
+            #[cfg(feature = "track_allocations")]
+            total_bytes_allocated: 0,
+            #[cfg(feature = "track_allocations")]
+            alloc_count: 0,
+        }
+    }
+
+    /// Snapshots `total_time`/`measure_count` deltas accumulated since the previous frame into
+    /// the rolling window, then advances the baseline. Called once per rendered frame via
+    /// `Profiler::next_frame`.
+    pub(crate) fn advance_frame(&mut self) {
+        let total_delta = self.total_time - self.prev_total_time;
+        let count_delta = self.measure_count - self.prev_measure_count;
+
+        if self.frame_window_size > 0 {
+            if self.frame_window.len() >= self.frame_window_size {
+                self.frame_window.pop_front();
+            }
+            self.frame_window.push_back((total_delta, count_delta));
+        }
+
+        self.prev_total_time = self.total_time;
+        self.prev_measure_count = self.measure_count;
+
+        for child in self.children.values_mut() {
+            child.advance_frame();
+        }
+    }
+
+    /// Recursively zeroes out accumulated timing (and allocation, if enabled) statistics,
+    /// keeping the block tree itself intact. Called via `ProfilerData::reset_stats`.
+    pub(crate) fn reset(&mut self) {
+        self.total_time = Duration::from_millis(0);
+        self.measure_count = 0;
+        self.prev_total_time = Duration::from_millis(0);
+        self.prev_measure_count = 0;
+        self.frame_window.clear();
+
+        #[cfg(feature = "track_allocations")]
+        {
+            self.total_bytes_allocated = 0;
+            self.alloc_count = 0;
+        }
+
+        for child in self.children.values_mut() {
+            child.reset();
         }
     }
 
@@ -62,7 +146,17 @@ impl BlockStat {
     }
 
     fn build_report_recurse(&self, total_global_time: Duration, avg_global_time: Duration, total_parent_time: Duration, avg_parent_time: Duration) -> BlockStatReport {
-        let avg_time = self.total_time / self.measure_count;
+        // A block can end up in the tree with `measure_count == 0` when every sample seen so far
+        // was dropped by the `min_duration` filter before it could be merged in; guard the
+        // division the same way `bytes_per_call` already does below.
+        let avg_time = self.total_time.checked_div(self.measure_count).unwrap_or(Duration::from_millis(0));
+
+        let last_frame_time = self.frame_window.back().map(|&(time, _)| time).unwrap_or(Duration::from_millis(0));
+        let windowed_max_time = self.frame_window.iter().map(|&(time, _)| time).max().unwrap_or(Duration::from_millis(0));
+        let windowed_avg_time = match self.frame_window.len() {
+            0 => Duration::from_millis(0),
+            len => self.frame_window.iter().map(|&(time, _)| time).sum::<Duration>() / len as u32,
+        };
 
         BlockStatReport {
             name: {
@@ -98,6 +192,15 @@ impl BlockStat {
             avg_time,
             global_percents: (self.total_time.as_secs_f32() / total_global_time.as_secs_f32())*100.0,
             relative_parent_percents: (self.total_time.as_secs_f32() / total_parent_time.as_secs_f32())*100.0,
+            last_frame_time,
+            windowed_avg_time,
+            windowed_max_time,
+            #[cfg(feature = "track_allocations")]
+            total_bytes_allocated: self.total_bytes_allocated,
+            #[cfg(feature = "track_allocations")]
+            bytes_per_call: self.total_bytes_allocated.checked_div(self.measure_count as u64).unwrap_or(0),
+            #[cfg(feature = "track_allocations")]
+            alloc_count: self.alloc_count,
             children: {
                 let total_parent_time: Duration = self.total_time;
                 let avg_parent_time: Duration = avg_time;
@@ -108,3 +211,20 @@ impl BlockStat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_report_does_not_panic_on_a_zero_measure_count_child() {
+        // A block whose every sample was dropped by `Filter::min_duration` still ends up in the
+        // tree with `measure_count == 0`; `avg_time` must not divide by that zero.
+        let mut root = BlockStat::new("root", 0);
+        root.total_time = Duration::from_millis(10);
+        root.measure_count = 1;
+        root.children.insert(1, BlockStat::new("always_filtered_out", 0));
+
+        root.build_report();
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+/// Describes which blocks `Profiler` should actually record, so a flood of begin/end events
+/// from tiny, hot blocks doesn't drown out the signal and add overhead.
+///
+/// A spec string has the form `"name1|name2@depth>duration"`, where every part is optional:
+/// - `name1|name2` is an allow-list of block names; only these blocks and their descendants are
+///   recorded. An empty (or omitted) allow-list means "record everything".
+/// - `@depth` is the maximum nesting depth; `profile_block!` guards deeper than this become
+///   no-ops.
+/// - `>duration` (e.g. `>0.5ms`) is a minimum duration threshold; measured blocks shorter than
+///   this are dropped before being merged into `BlockStat`.
+#[derive(Default)]
+pub struct Filter {
+    pub(crate) allowed_names: Vec<String>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) min_duration: Option<Duration>,
+}
+
+impl Filter {
+    pub fn new(allowed_names: Vec<String>, max_depth: Option<usize>, min_duration: Option<Duration>) -> Filter {
+        Filter {
+            allowed_names,
+            max_depth,
+            min_duration,
+        }
+    }
+
+    /// Parses a filter spec. Malformed `@depth`/`>duration` numbers are ignored (treated as
+    /// absent) rather than panicking, since the spec is typically hand-typed.
+    pub fn from_spec(spec: &str) -> Filter {
+        let mut remainder = spec;
+
+        let min_duration = remainder.find('>').and_then(|pos| {
+            let duration_str = &remainder[pos + 1..];
+            remainder = &remainder[..pos];
+
+            duration_str.trim_end_matches("ms").parse::<f64>().ok().map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        });
+
+        let max_depth = remainder.find('@').and_then(|pos| {
+            let depth_str = &remainder[pos + 1..];
+            remainder = &remainder[..pos];
+
+            depth_str.parse::<usize>().ok()
+        });
+
+        let allowed_names = match remainder.is_empty() {
+            true => Vec::new(),
+            false => remainder.split('|').map(|name| name.to_owned()).collect(),
+        };
+
+        Filter {
+            allowed_names,
+            max_depth,
+            min_duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_spec_parses_name_allow_list_depth_and_duration() {
+        let filter = Filter::from_spec("render|physics@3>0.5ms");
+
+        assert_eq!(filter.allowed_names, vec!["render".to_owned(), "physics".to_owned()]);
+        assert_eq!(filter.max_depth, Some(3));
+        assert_eq!(filter.min_duration, Some(Duration::from_micros(500)));
+    }
+
+    #[test]
+    fn from_spec_depth_only() {
+        let filter = Filter::from_spec("@5");
+
+        assert!(filter.allowed_names.is_empty());
+        assert_eq!(filter.max_depth, Some(5));
+        assert_eq!(filter.min_duration, None);
+    }
+
+    #[test]
+    fn from_spec_duration_only() {
+        let filter = Filter::from_spec(">1.5ms");
+
+        assert!(filter.allowed_names.is_empty());
+        assert_eq!(filter.max_depth, None);
+        assert_eq!(filter.min_duration, Some(Duration::from_micros(1500)));
+    }
+
+    #[test]
+    fn from_spec_empty_is_default() {
+        let filter = Filter::from_spec("");
+
+        assert!(filter.allowed_names.is_empty());
+        assert_eq!(filter.max_depth, None);
+        assert_eq!(filter.min_duration, None);
+    }
+
+    #[test]
+    fn from_spec_ignores_malformed_depth_and_duration() {
+        let filter = Filter::from_spec("render@notanumber>notaduration");
+
+        assert_eq!(filter.allowed_names, vec!["render".to_owned()]);
+        assert_eq!(filter.max_depth, None);
+        assert_eq!(filter.min_duration, None);
+    }
+}
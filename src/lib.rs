@@ -1,8 +1,10 @@
 //! A library for simple profiling your code with HTML reports as result.
 //!
 //! # Usage
-//! At first the rprofiler must be initialized by the call `rprofiler::PROFILER.initialize()` method.
-//! This method is returned an object of ProfilerData struct, where will be gathering all runtime information.
+//! At first the rprofiler must be initialized by the call `rprofiler::PROFILER.initialize(frame_window_size)` method.
+//! `frame_window_size` sets how many past frames each block keeps in its rolling window for per-frame
+//! statistics; pass `0` if you don't need them. This method is returned an object of ProfilerData struct,
+//! where will be gathering all runtime information.
 //! Then you can use special `profile_block` macro for profiling blocks of your code. It has some syntax variations:
 //! ```rust
 //! profile_block!();
@@ -15,8 +17,15 @@
 //! You should call the `rprofiler::PROFILER.process_events(...)` method periodically to process events and clear the queue.
 //! As example, this method can be called at end of each game frame.
 //!
+//! If you passed a non-zero `frame_window_size` to `initialize`, call `rprofiler::PROFILER.next_frame()`
+//! once per rendered frame (after `process_events`) to snapshot that frame's per-block timings into the
+//! rolling window instead of only ever seeing lifetime averages.
+//!
 //! At end of profiling you should call the `rprofiler::PROFILER.shutdown(...)` method.
 //! It will process all gathered information and save result as HTML document into specified file.
+//! If you'd rather inspect the raw, time-ordered event stream (e.g. in `chrome://tracing` or
+//! Perfetto), call `rprofiler::PROFILER.shutdown_trace(...)` instead to save it as a Chrome
+//! Trace Event Format JSON file.
 //!
 //! You can disable all profiling at compile-time by enabling a feature *"disable_profiling"* in *Cargo.toml* of your project.
 //! ```toml
@@ -30,6 +39,21 @@
 //! features = ["disable_profiling"]
 //! ```
 //!
+//! Enabling the *"track_allocations"* feature turns rprofiler into a combined time+memory
+//! profiler: install `rprofiler::TrackingAllocator` as your `#[global_allocator]` and every block
+//! gains `total_bytes_allocated`/`alloc_count` stats next to its timing columns.
+//! ```rust,ignore
+//! #[global_allocator]
+//! static ALLOCATOR: rprofiler::TrackingAllocator = rprofiler::TrackingAllocator::new();
+//! ```
+//!
+//! Enabling the *"tracing_backend"* feature makes every `profile_block!` also open a
+//! `tracing::span!` (`Level::INFO`, target *"rprofiler"*, carrying the block name as a field) for
+//! the lifetime of the guard, alongside the native per-thread block recording. This routes blocks into
+//! whatever `tracing-subscriber` pipeline the host application already has set up, and stays
+//! meaningful for async code where the thread-id-stack model used by the native HTML report is
+//! otherwise unreliable.
+//!
 //! # Examples
 //! ```rust
 //! fn factorial(value: i32) -> i32 {
@@ -45,13 +69,14 @@
 //! }
 //!
 //! fn main() {
-//!     let mut profiler_data = PROFILER.initialize();
+//!     let mut profiler_data = PROFILER.initialize(120);
 //!
 //!     for _ in 0..1000 {
 //!         for _ in 0..1_000_000 {
 //!             test_func();
 //!         }
 //!         PROFILER.process_events(&mut profiler_data);
+//!         PROFILER.next_frame();
 //!     }
 //!
 //!     PROFILER.shutdown("./profiler_report.html", &mut profiler_data);
@@ -63,10 +88,16 @@ extern crate flume;
 
 pub use profiler::{ Profiler, PROFILER, ProfilerBlockGuard };
 pub use profiler_data::ProfilerData;
+pub use filter::Filter;
+#[cfg(feature = "track_allocations")]
+pub use tracking_allocator::TrackingAllocator;
 
 mod profiler;
 mod block_stat;
 mod profiler_data;
+mod filter;
+#[cfg(feature = "track_allocations")]
+mod tracking_allocator;
 
 use block_stat::*;
 
@@ -1,13 +1,14 @@
 use crate::{
-    BlockStat, ProfilerData,
+    BlockStat, Filter, ProfilerData,
 };
+use crate::profiler_data::TracePhase;
 use flume::{
     Sender, Receiver,
 };
 use std::{
-    time::{
-        Duration, Instant,
-    },
+    cell::Cell,
+    sync::{ Arc, Mutex, RwLock },
+    time::{ Duration, Instant },
     thread::ThreadId,
 };
 
@@ -15,25 +16,53 @@ lazy_static! {
     pub static ref PROFILER: Profiler = Profiler::new();
 }
 
+#[cfg(not(feature = "disable_profiling"))]
+lazy_static! {
+    static ref START_INSTANT: Instant = Instant::now();
+}
+
+#[cfg(not(feature = "disable_profiling"))]
+thread_local! {
+    static BLOCK_DEPTH: Cell<usize> = Cell::new(0);
+    static ALLOWED_NESTING: Cell<usize> = Cell::new(0);
+    static THREAD_RECORDS: Arc<Mutex<Vec<Record>>> = {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        PROFILER.register_thread_records(std::thread::current().id(), Arc::clone(&records));
+        records
+    };
+}
+
+#[cfg(not(feature = "disable_profiling"))]
+enum RecordKind {
+    Begin,
+    End,
+}
+
+#[cfg(not(feature = "disable_profiling"))]
+struct Record {
+    kind: RecordKind,
+    name: &'static str,
+    timestamp_ns: u64,
+    #[cfg(feature = "track_allocations")]
+    bytes_allocated: u64,
+    #[cfg(feature = "track_allocations")]
+    alloc_count: u64,
+}
+
 #[cfg(not(feature = "disable_profiling"))]
 enum ProfilerEvent {
-    Initialize(Instant),
+    Initialize(Instant, usize),
     Shutdown(Instant),
     ResetStats,
-    BeginBlock {
-        thread_id: ThreadId,
-        name: &'static str,
-    },
-    EndBlock {
-        thread_id: ThreadId,
-        time: Duration,
-    },
+    NextFrame,
 }
 
 #[cfg(not(feature = "disable_profiling"))]
 pub struct Profiler {
     events_sender: Sender<ProfilerEvent>,
     events_receiver: Receiver<ProfilerEvent>,
+    filter: RwLock<Filter>,
+    thread_records: Mutex<Vec<(ThreadId, Arc<Mutex<Vec<Record>>>)>>,
 }
 
 #[cfg(feature = "disable_profiling")]
@@ -44,48 +73,106 @@ impl Profiler {
     pub fn process_events(&self, data: &mut ProfilerData) {
         crate::profile_block!();
 
+        // `Initialize` has to be applied before the per-thread records below are drained, since it
+        // sets the `frame_window_size` new `BlockStat`s are created with. Every other event
+        // (`ResetStats`, `Shutdown`, `NextFrame`) is deferred until after draining instead: those
+        // records were pushed to their thread-local buffer before this call, i.e. before the
+        // event, so they must be merged in first or a `ResetStats`/`Shutdown` that ran first would
+        // zero the tree only for the stale records to immediately repopulate it.
+        let mut deferred_events = Vec::new();
+
         for event in self.events_receiver.try_iter() {
             match event {
-                ProfilerEvent::Initialize(time) => {
+                ProfilerEvent::Initialize(time, frame_window_size) => {
                     data.main_block_start_time = time;
+                    data.main_block_start_ns = time.duration_since(*START_INSTANT).as_nanos() as u64;
+                    data.frame_window_size = frame_window_size;
+                    data.main_block.frame_window_size = frame_window_size;
                 },
+                other => deferred_events.push(other),
+            }
+        }
+
+        for (thread_id, records) in self.thread_records.lock().unwrap().iter() {
+            let records = std::mem::take(&mut *records.lock().unwrap());
+
+            for record in records {
+                match record.kind {
+                    RecordKind::Begin => {
+                        let name = record.name;
+                        let name_hash = (name as *const str as *const u8) as usize;
+                        let frame_window_size = data.frame_window_size;
+                        let block_stat = match data.current_block_on_thread(*thread_id) {
+                            Some(top_block_stat) => {
+                                let top_block_stat = unsafe { &mut *top_block_stat };
+                                let block_stat = top_block_stat.children.entry(name_hash).or_insert_with(|| BlockStat::new(name, frame_window_size));
+                                block_stat as *mut _
+                            },
+                            None => {
+                                let block_stat = data.main_block.children.entry(name_hash).or_insert_with(|| BlockStat::new(name, frame_window_size));
+                                block_stat as *mut _
+                            },
+                        };
+
+                        data.push_block_to_thread_stack(*thread_id, block_stat, record.timestamp_ns);
+                        data.push_trace_event(name, TracePhase::Begin, record.timestamp_ns, *thread_id);
+                    },
+                    RecordKind::End => {
+                        let (thread_current_block, begin_timestamp_ns) = data.pop_block_from_thread_stack(*thread_id).unwrap();
+                        let thread_current_block = unsafe { &mut *thread_current_block };
+                        let duration = Duration::from_nanos(record.timestamp_ns - begin_timestamp_ns);
+
+                        let min_duration = self.filter.read().unwrap().min_duration;
+                        if min_duration.map_or(true, |min_duration| duration >= min_duration) {
+                            thread_current_block.total_time += duration;
+                            thread_current_block.measure_count += 1;
+
+                            #[cfg(feature = "track_allocations")]
+                            {
+                                thread_current_block.total_bytes_allocated += record.bytes_allocated;
+                                thread_current_block.alloc_count += record.alloc_count;
+                            }
+                        }
+
+                        data.push_trace_event(thread_current_block.name, TracePhase::End, record.timestamp_ns, *thread_id);
+                    },
+                }
+            }
+        }
+
+        for event in deferred_events {
+            match event {
+                ProfilerEvent::Initialize(..) => unreachable!("Initialize is handled before draining thread records"),
                 ProfilerEvent::Shutdown(time) => {
                     data.main_block.total_time = time.duration_since(data.main_block_start_time);
                     data.main_block.measure_count = 1;
-                }
-                ProfilerEvent::ResetStats => data.reset_stats(),
-                ProfilerEvent::BeginBlock { thread_id, name } => {
-                    let name_hash = (name as *const str as *const u8) as usize;
-                    let block_stat = match data.current_block_on_thread(thread_id) {
-                        Some(top_block_stat) => {
-                            let top_block_stat = unsafe { &mut *top_block_stat };
-                            let block_stat = top_block_stat.children.entry(name_hash).or_insert_with(|| Box::new(BlockStat::new(name)));
-                            block_stat.as_mut() as *mut _
-                        },
-                        None => {
-                            let block_stat = data.main_block.children.entry(name_hash).or_insert_with(|| Box::new(BlockStat::new(name)));
-                            block_stat.as_mut() as *mut _
-                        },
-                    };
-
-                    data.push_block_to_thread_stack(thread_id, block_stat);
-                },
-                ProfilerEvent::EndBlock { thread_id, time } => {
-                    let thread_current_block = data.pop_block_from_thread_stack(thread_id).unwrap();
-                    let thread_current_block = unsafe { &mut *thread_current_block };
-                    thread_current_block.total_time += time;
-                    thread_current_block.measure_count += 1;
                 },
+                ProfilerEvent::ResetStats => data.reset_stats(),
+                ProfilerEvent::NextFrame => data.main_block.advance_frame(),
             }
         }
     }
 
-    pub fn initialize(&self) -> ProfilerData {
-        self.events_sender.send(ProfilerEvent::Initialize(Instant::now())).unwrap();
+    /// Initializes the profiler. `frame_window_size` sets how many past `next_frame` calls each
+    /// `BlockStat` keeps in its rolling window; pass `0` to disable per-frame statistics.
+    pub fn initialize(&self, frame_window_size: usize) -> ProfilerData {
+        // Force `START_INSTANT` into existence before taking this timestamp. It otherwise only
+        // gets lazily initialized by the first `push_record` (including `process_events`'s own
+        // self-instrumentation), which always happens after `initialize` returns, so
+        // `main_block_start_ns` would saturate to zero instead of reflecting real elapsed time.
+        lazy_static::initialize(&START_INSTANT);
+        self.events_sender.send(ProfilerEvent::Initialize(Instant::now(), frame_window_size)).unwrap();
 
         ProfilerData::new()
     }
 
+    /// Snapshots the deltas accumulated since the previous call into each block's rolling
+    /// window. Call this once per rendered frame to watch per-frame spikes without them being
+    /// diluted by the lifetime average.
+    pub fn next_frame(&self) {
+        self.events_sender.send(ProfilerEvent::NextFrame).unwrap();
+    }
+
     pub fn shutdown(&self, report_path: &str, profiler_data: &mut ProfilerData) {
         self.events_sender.send(ProfilerEvent::Shutdown(Instant::now())).unwrap();
 
@@ -93,32 +180,56 @@ impl Profiler {
         std::fs::write(report_path, profiler_data.build_report_string()).unwrap();
     }
 
+    /// Same as `shutdown`, but writes the raw time-ordered event stream as Chrome Trace Event
+    /// Format JSON instead of the aggregated HTML report, so it can be loaded into
+    /// `chrome://tracing` or Perfetto.
+    pub fn shutdown_trace(&self, trace_path: &str, profiler_data: &mut ProfilerData) {
+        self.events_sender.send(ProfilerEvent::Shutdown(Instant::now())).unwrap();
+
+        self.process_events(profiler_data);
+        std::fs::write(trace_path, profiler_data.build_trace_json()).unwrap();
+    }
+
     pub fn reset_stats(&self) {
         self.events_sender.send(ProfilerEvent::ResetStats).unwrap();
     }
 
+    /// Sets the `Filter` consulted by every `profile_block!` guard and by `process_events` to
+    /// decide which blocks are actually recorded.
+    pub fn set_filter(&self, filter: Filter) {
+        *self.filter.write().unwrap() = filter;
+    }
+
     fn new() -> Profiler {
         let (events_sender, events_receiver) = flume::unbounded();
         Profiler {
             events_sender,
             events_receiver,
+            filter: RwLock::new(Filter::default()),
+            thread_records: Mutex::new(Vec::new()),
         }
     }
 
+    fn register_thread_records(&self, thread_id: ThreadId, records: Arc<Mutex<Vec<Record>>>) {
+        self.thread_records.lock().unwrap().push((thread_id, records));
+    }
+
+    #[cfg(not(feature = "track_allocations"))]
     #[inline]
-    fn begin_block(&self, name: &'static str) {
-        self.events_sender.send(ProfilerEvent::BeginBlock {
-            thread_id: std::thread::current().id(),
-            name,
-        }).unwrap();
+    fn push_record(&self, name: &'static str, kind: RecordKind) {
+        let timestamp_ns = Instant::now().duration_since(*START_INSTANT).as_nanos() as u64;
+        THREAD_RECORDS.with(|records| {
+            records.lock().unwrap().push(Record { kind, name, timestamp_ns });
+        });
     }
 
+    #[cfg(feature = "track_allocations")]
     #[inline]
-    fn end_block(&self, time: Duration) {
-        self.events_sender.send(ProfilerEvent::EndBlock {
-            thread_id: std::thread::current().id(),
-            time,
-        }).unwrap();
+    fn push_record(&self, name: &'static str, kind: RecordKind, bytes_allocated: u64, alloc_count: u64) {
+        let timestamp_ns = Instant::now().duration_since(*START_INSTANT).as_nanos() as u64;
+        THREAD_RECORDS.with(|records| {
+            records.lock().unwrap().push(Record { kind, name, timestamp_ns, bytes_allocated, alloc_count });
+        });
     }
 }
 
@@ -126,28 +237,34 @@ impl Profiler {
 impl Profiler {
     pub fn process_events(&self, _data: &mut ProfilerData) {}
 
-    pub fn initialize(&self) -> ProfilerData {
+    pub fn initialize(&self, _frame_window_size: usize) -> ProfilerData {
         ProfilerData::new()
     }
 
+    pub fn next_frame(&self) {}
+
     pub fn shutdown(&self, _report_path: &str, _profiler_data: &mut ProfilerData) {}
 
+    pub fn shutdown_trace(&self, _trace_path: &str, _profiler_data: &mut ProfilerData) {}
+
     pub fn reset_stats(&self) {}
 
+    pub fn set_filter(&self, _filter: Filter) {}
+
     fn new() -> Profiler {
         Profiler
     }
-
-    #[inline]
-    fn begin_block(&self, _name: &'static str) {}
-
-    #[inline]
-    fn end_block(&self, _time: Duration) {}
 }
 
 #[cfg(not(feature = "disable_profiling"))]
 pub struct ProfilerBlockGuard {
-    start_time: Instant,
+    recording: bool,
+    entered_allowed_scope: bool,
+    name: &'static str,
+    #[cfg(feature = "track_allocations")]
+    begin_alloc_stats: (u64, u64),
+    #[cfg(feature = "tracing_backend")]
+    _span: tracing::span::EnteredSpan,
 }
 
 #[cfg(feature = "disable_profiling")]
@@ -165,11 +282,51 @@ impl ProfilerBlockGuard {
 impl ProfilerBlockGuard {
     #[inline]
     pub fn new(block_name: &'static str) -> ProfilerBlockGuard {
-        let guard = ProfilerBlockGuard {
-            start_time: Instant::now(),
+        let depth = BLOCK_DEPTH.with(|depth| depth.get());
+
+        let (name_allowed, depth_allowed) = {
+            let filter = PROFILER.filter.read().unwrap();
+            let name_allowed = filter.allowed_names.is_empty()
+                || ALLOWED_NESTING.with(|nesting| nesting.get() > 0)
+                || filter.allowed_names.iter().any(|name| name == block_name);
+            let depth_allowed = filter.max_depth.map_or(true, |max_depth| depth < max_depth);
+            (name_allowed, depth_allowed)
+        };
+
+        BLOCK_DEPTH.with(|d| d.set(depth + 1));
+        if name_allowed {
+            ALLOWED_NESTING.with(|nesting| nesting.set(nesting.get() + 1));
+        }
+
+        let recording = name_allowed && depth_allowed;
+
+        #[cfg(feature = "track_allocations")]
+        let begin_alloc_stats = match recording {
+            true => crate::tracking_allocator::thread_alloc_stats(),
+            false => (0, 0),
         };
-        PROFILER.begin_block(block_name);
-        guard
+
+        #[cfg(not(feature = "track_allocations"))]
+        if recording {
+            PROFILER.push_record(block_name, RecordKind::Begin);
+        }
+        #[cfg(feature = "track_allocations")]
+        if recording {
+            PROFILER.push_record(block_name, RecordKind::Begin, 0, 0);
+        }
+
+        #[cfg(feature = "tracing_backend")]
+        let _span = tracing::span!(target: "rprofiler", tracing::Level::INFO, "profile_block", name = block_name).entered();
+
+        ProfilerBlockGuard {
+            recording,
+            entered_allowed_scope: name_allowed,
+            name: block_name,
+            #[cfg(feature = "track_allocations")]
+            begin_alloc_stats,
+            #[cfg(feature = "tracing_backend")]
+            _span,
+        }
     }
 }
 
@@ -177,6 +334,21 @@ impl ProfilerBlockGuard {
 impl Drop for ProfilerBlockGuard {
     #[inline]
     fn drop(&mut self) {
-        PROFILER.end_block(self.start_time.elapsed());
+        BLOCK_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        if self.entered_allowed_scope {
+            ALLOWED_NESTING.with(|nesting| nesting.set(nesting.get() - 1));
+        }
+        if self.recording {
+            #[cfg(not(feature = "track_allocations"))]
+            PROFILER.push_record(self.name, RecordKind::End);
+
+            #[cfg(feature = "track_allocations")]
+            {
+                let (end_bytes, end_count) = crate::tracking_allocator::thread_alloc_stats();
+                let bytes_allocated = end_bytes - self.begin_alloc_stats.0;
+                let alloc_count = end_count - self.begin_alloc_stats.1;
+                PROFILER.push_record(self.name, RecordKind::End, bytes_allocated, alloc_count);
+            }
+        }
     }
 }
@@ -2,8 +2,21 @@ use crate::BlockStat;
 use std::{
     time::Instant,
     thread::ThreadId,
+    collections::HashMap,
 };
 
+pub(crate) enum TracePhase {
+    Begin,
+    End,
+}
+
+pub(crate) struct TraceEvent {
+    pub(crate) name: &'static str,
+    pub(crate) phase: TracePhase,
+    pub(crate) timestamp_micros: u64,
+    pub(crate) thread_id_value: usize,
+}
+
 const HTML_REPORT_HEADER: &str = r#"<html><body>
 <title>Profile report</title>
 
@@ -32,32 +45,58 @@ const HTML_REPORT_HEADER: &str = r#"<html><body>
 
 const HTML_REPORT_FOOTER: &str = "</body></html>";
 
-#[inline]
-fn thread_id_to_usize(thread_id: ThreadId) -> usize {
-    unsafe { *(&thread_id as *const ThreadId as *const usize) }
-}
-
 pub struct ProfilerData {
     pub(crate) main_block_start_time: Instant,
+    pub(crate) main_block_start_ns: u64,
     pub(crate) main_block: BlockStat,
-    pub(crate) blocks_stack: Vec<Vec<*mut BlockStat>>,
+    pub(crate) blocks_stack: Vec<Vec<(*mut BlockStat, u64)>>,
+    pub(crate) trace_events: Vec<TraceEvent>,
+    pub(crate) frame_window_size: usize,
+    thread_indices: HashMap<ThreadId, usize>,
 }
 
 impl ProfilerData {
     pub(crate) fn new() -> ProfilerData {
         ProfilerData {
             main_block_start_time: Instant::now(),
-            main_block: BlockStat::new("ProfilerMainBlock"),
+            main_block_start_ns: 0,
+            main_block: BlockStat::new("ProfilerMainBlock", 0),
             blocks_stack: Vec::new(),
+            trace_events: Vec::new(),
+            frame_window_size: 0,
+            thread_indices: HashMap::new(),
         }
     }
 
+    /// Zeroes out every accumulated timing (and allocation, if enabled) statistic across the
+    /// whole block tree, without discarding the tree shape itself, and drops any buffered trace
+    /// events. Triggered by `Profiler::reset_stats`.
+    pub(crate) fn reset_stats(&mut self) {
+        self.main_block.reset();
+        self.trace_events.clear();
+    }
+
+    /// Maps a `ThreadId` to a small, dense, monotonically increasing slot used to index
+    /// `blocks_stack`, assigning a fresh slot the first time a given thread is seen. This avoids
+    /// reinterpreting the opaque, platform-defined bits of `ThreadId` as an index, which would be
+    /// undefined behavior and could also be arbitrarily large.
+    #[inline]
+    fn thread_index(&mut self, thread_id: ThreadId) -> usize {
+        let next_index = self.thread_indices.len();
+        *self.thread_indices.entry(thread_id).or_insert(next_index)
+    }
+
     pub(crate) fn build_report_string(&self) -> String {
         let mut report = String::with_capacity(8192);
         report += HTML_REPORT_HEADER;
 
         report += "<table>\n";
-        report += "<thead><th>Block name</th><th>Global percents</th><th>Relative to parent percents</th><th>Average time</th></thead>\n";
+        report += "<thead><th>Block name</th><th>Global percents</th><th>Relative to parent percents</th><th>Average time</th><th>Last frame time</th><th>Windowed average time</th><th>Windowed max time</th>";
+        #[cfg(feature = "track_allocations")]
+        {
+            report += "<th>Total bytes allocated</th><th>Bytes per call</th><th>Allocation count</th>";
+        }
+        report += "</thead>\n";
 
         self.main_block.build_report().build_string(&mut report);
 
@@ -67,28 +106,64 @@ impl ProfilerData {
         report
     }
 
+    pub(crate) fn build_trace_json(&self) -> String {
+        let mut json = String::with_capacity(self.trace_events.len()*64 + 2);
+        json += "[\n";
+
+        for (i, event) in self.trace_events.iter().enumerate() {
+            if i > 0 {
+                json += ",\n";
+            }
+
+            json += &format!(
+                "{{\"name\": \"{}\", \"ph\": \"{}\", \"ts\": {}, \"pid\": 0, \"tid\": {}, \"cat\": \"fn\"}}",
+                event.name.replace('\\', "\\\\").replace('"', "\\\""),
+                match event.phase {
+                    TracePhase::Begin => "B",
+                    TracePhase::End => "E",
+                },
+                event.timestamp_micros,
+                event.thread_id_value,
+            );
+        }
+
+        json += "\n]";
+        json
+    }
+
     #[inline]
-    pub(crate) fn current_block_on_thread(&self, thread_id: ThreadId) -> Option<*mut BlockStat> {
-        let thread_id_value = thread_id_to_usize(thread_id);
-        self.blocks_stack.get(thread_id_value).and_then(|a| a.last().cloned())
+    pub(crate) fn current_block_on_thread(&mut self, thread_id: ThreadId) -> Option<*mut BlockStat> {
+        let thread_index = self.thread_index(thread_id);
+        self.blocks_stack.get(thread_index).and_then(|a| a.last().map(|&(block, _)| block))
     }
 
     #[inline]
-    pub(crate) fn push_block_to_thread_stack(&mut self, thread_id: ThreadId, block: *mut BlockStat) {
-        let thread_id_value = thread_id_to_usize(thread_id);
+    pub(crate) fn push_block_to_thread_stack(&mut self, thread_id: ThreadId, block: *mut BlockStat, timestamp_ns: u64) {
+        let thread_index = self.thread_index(thread_id);
 
-        if self.blocks_stack.len() < thread_id_value + 1 {
-            self.blocks_stack.resize(thread_id_value + 1, Vec::new());
+        if self.blocks_stack.len() < thread_index + 1 {
+            self.blocks_stack.resize(thread_index + 1, Vec::new());
         }
 
         unsafe {
-            self.blocks_stack.get_unchecked_mut(thread_id_value).push(block);
+            self.blocks_stack.get_unchecked_mut(thread_index).push((block, timestamp_ns));
         }
     }
 
     #[inline]
-    pub(crate) fn pop_block_from_thread_stack(&mut self, thread_id: ThreadId) -> Option<*mut BlockStat> {
-        let thread_id_value = thread_id_to_usize(thread_id);
-        self.blocks_stack.get_mut(thread_id_value).and_then(|a| a.pop())
+    pub(crate) fn pop_block_from_thread_stack(&mut self, thread_id: ThreadId) -> Option<(*mut BlockStat, u64)> {
+        let thread_index = self.thread_index(thread_id);
+        self.blocks_stack.get_mut(thread_index).and_then(|a| a.pop())
+    }
+
+    #[inline]
+    pub(crate) fn push_trace_event(&mut self, name: &'static str, phase: TracePhase, timestamp_ns: u64, thread_id: ThreadId) {
+        let thread_id_value = self.thread_index(thread_id);
+        self.trace_events.push(TraceEvent {
+            name,
+            phase,
+            timestamp_micros: timestamp_ns.saturating_sub(self.main_block_start_ns) / 1000,
+            thread_id_value,
+        });
     }
 }
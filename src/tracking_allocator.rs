@@ -0,0 +1,76 @@
+use std::{
+    alloc::{ GlobalAlloc, Layout, System },
+    cell::Cell,
+};
+
+thread_local! {
+    static ALLOC_STATS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+/// A `#[global_allocator]` wrapper that forwards every call to an inner allocator (`System` by
+/// default) and additionally maintains per-thread counters of bytes allocated and allocation
+/// count, so `ProfilerBlockGuard` can attribute memory allocation to the block it measures.
+pub struct TrackingAllocator<A = System> {
+    inner: A,
+}
+
+impl TrackingAllocator<System> {
+    pub const fn new() -> TrackingAllocator<System> {
+        TrackingAllocator { inner: System }
+    }
+}
+
+impl Default for TrackingAllocator<System> {
+    fn default() -> TrackingAllocator<System> {
+        TrackingAllocator::new()
+    }
+}
+
+impl<A> TrackingAllocator<A> {
+    pub const fn wrapping(inner: A) -> TrackingAllocator<A> {
+        TrackingAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record(layout.size() as u64);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(layout.size() as u64);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() && new_size > layout.size() {
+            record((new_size - layout.size()) as u64);
+        }
+        new_ptr
+    }
+}
+
+#[inline]
+fn record(bytes: u64) {
+    ALLOC_STATS.with(|stats| {
+        let (total_bytes, total_count) = stats.get();
+        stats.set((total_bytes + bytes, total_count + 1));
+    });
+}
+
+#[inline]
+pub(crate) fn thread_alloc_stats() -> (u64, u64) {
+    ALLOC_STATS.with(|stats| stats.get())
+}